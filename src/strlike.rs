@@ -2,6 +2,7 @@ use std::borrow::{Borrow, BorrowMut};
 use std::ffi::{CStr, FromBytesWithNulError};
 use std::fmt;
 use std::mem;
+use std::ops::Deref;
 use std::str::{Utf8Error, from_utf8, from_utf8_unchecked};
 
 use bow::ToBox;
@@ -38,6 +39,25 @@ pub trait StrLike: Len + ToOwned + DefaultRef {
 
     /// Similar to `from_data`, ignoring validity checking.
     unsafe fn from_data_unchecked(data: &Self::Data) -> &Self;
+
+    /// Whether splitting `to_data()` at `index` yields two valid halves.
+    ///
+    /// The default implementation treats every index as a boundary, which is
+    /// correct for data with no internal structure such as `[T]`.
+    fn is_boundary(&self, index: usize) -> bool {
+        let _ = index;
+        true
+    }
+
+    /// The closest boundary at or before `index`.
+    ///
+    /// Callers that split or truncate packed data at an arbitrary offset can
+    /// use this to snap the offset to one where `from_data_unchecked` is
+    /// sound. The default implementation is the identity, consistent with
+    /// the default `is_boundary`.
+    fn boundary_before(&self, index: usize) -> usize {
+        index
+    }
 }
 
 /// Extension to `StrLike`: types where concatenating data is equivalent to concatenating strings.
@@ -56,6 +76,24 @@ pub trait StrLikeMut: StrLike
 
     /// Mutable version of `from_data_unchecked`.
     unsafe fn from_data_mut_unchecked(data: &mut Self::Data) -> &mut Self;
+
+    /// Converts ASCII letters in this string to uppercase, in place, without reallocating.
+    ///
+    /// Only bytes in the ASCII range are touched, which is guaranteed to
+    /// preserve length and validity for `str`, WTF-8 `OsStr`, and `CStr`:
+    /// every byte of a multi-byte sequence has its high bit set, and the
+    /// trailing nul of a `CStr` is not a letter, so none of them change and
+    /// `to_data_mut` is safe to use here.
+    fn make_ascii_uppercase(&mut self) where Self::Data: AsMut<[u8]> {
+        unsafe { self.to_data_mut() }.as_mut().make_ascii_uppercase();
+    }
+
+    /// Converts ASCII letters in this string to lowercase, in place, without reallocating.
+    ///
+    /// See `make_ascii_uppercase` for why this is sound.
+    fn make_ascii_lowercase(&mut self) where Self::Data: AsMut<[u8]> {
+        unsafe { self.to_data_mut() }.as_mut().make_ascii_lowercase();
+    }
 }
 
 impl<T: 'static + Copy> StrLike for [T] {
@@ -75,6 +113,18 @@ impl<T: 'static + Copy> StrLike for [T] {
     }
 }
 
+/// Walks `bytes` backward from `index`, skipping UTF-8 continuation bytes
+/// (`0b10xxxxxx`), to find the start of the code point or sequence
+/// containing `index`. Used for both UTF-8 (`str`) and WTF-8 (`OsStr`), whose
+/// continuation bytes share the same bit pattern.
+pub(crate) fn utf8_like_boundary_before(bytes: &[u8], index: usize) -> usize {
+    let mut i = index.min(bytes.len());
+    while i > 0 && i < bytes.len() && bytes[i] & 0b1100_0000 == 0b1000_0000 {
+        i -= 1;
+    }
+    i
+}
+
 impl StrLike for str {
     type Data = [u8];
     type OwnedData = Vec<u8>;
@@ -90,6 +140,13 @@ impl StrLike for str {
     unsafe fn from_data_unchecked(data: &[u8]) -> &str {
         from_utf8_unchecked(data)
     }
+
+    fn is_boundary(&self, index: usize) -> bool {
+        self.is_char_boundary(index)
+    }
+    fn boundary_before(&self, index: usize) -> usize {
+        utf8_like_boundary_before(self.as_bytes(), index)
+    }
 }
 
 impl StrLike for CStr {
@@ -112,6 +169,19 @@ impl StrLike for CStr {
 unsafe impl DataConcat for str {}
 unsafe impl<T: 'static + Copy> DataConcat for [T] {}
 
+impl StrLikeMut for CStr {
+    unsafe fn to_data_mut(&mut self) -> &mut [u8] {
+        mem::transmute(self)
+    }
+    fn from_data_mut(data: &mut [u8]) -> Result<&mut CStr, FromBytesWithNulError> {
+        CStr::from_bytes_with_nul(&*data)?;
+        Ok(unsafe { mem::transmute(data) })
+    }
+    unsafe fn from_data_mut_unchecked(data: &mut [u8]) -> &mut CStr {
+        mem::transmute(data)
+    }
+}
+
 impl<T: 'static + Copy> StrLikeMut for [T] {
     unsafe fn to_data_mut(&mut self) -> &mut [T] {
         self
@@ -136,3 +206,459 @@ impl StrLikeMut for str {
         mem::transmute(data)
     }
 }
+
+
+/// A validated UTF-16 string slice, the wide-string counterpart of `str`.
+///
+/// Unlike `str`, which is UTF-8, `WStr` stores `u16` code units and is the
+/// natural backend for platforms (Windows, XPCOM) that speak UTF-16. A `WStr`
+/// is guaranteed to contain only well-formed surrogate pairs: a high
+/// surrogate (`0xD800..=0xDBFF`) is always immediately followed by a low
+/// surrogate (`0xDC00..=0xDFFF`), exactly the rule `char::decode_utf16`
+/// enforces.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct WStr {
+    units: [u16],
+}
+
+/// Owned, growable counterpart of `WStr`, analogous to `String` for `str`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WString {
+    units: Vec<u16>,
+}
+
+/// Error returned when validating `u16` data for `WStr`: an unpaired surrogate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Utf16Error {
+    index: usize,
+}
+
+impl Utf16Error {
+    /// The index of the code unit that is not part of a valid surrogate pair.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+impl fmt::Display for Utf16Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unpaired surrogate at index {}", self.index)
+    }
+}
+
+/// Scans `units` for unpaired surrogates, per the rule `char::decode_utf16` enforces.
+fn validate_utf16(units: &[u16]) -> Result<(), Utf16Error> {
+    let mut i = 0;
+    while i < units.len() {
+        let unit = units[i];
+        if (0xD800..=0xDBFF).contains(&unit) {
+            match units.get(i + 1) {
+                Some(&low) if (0xDC00..=0xDFFF).contains(&low) => i += 2,
+                _ => return Err(Utf16Error { index: i }),
+            }
+        } else if (0xDC00..=0xDFFF).contains(&unit) {
+            return Err(Utf16Error { index: i });
+        } else {
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+impl WStr {
+    /// Wraps `units` as a `WStr` without checking surrogate pairing.
+    pub unsafe fn from_units_unchecked(units: &[u16]) -> &WStr {
+        &*(units as *const [u16] as *const WStr)
+    }
+
+    /// Validates `units` and wraps them as a `WStr`.
+    pub fn from_units(units: &[u16]) -> Result<&WStr, Utf16Error> {
+        validate_utf16(units)?;
+        Ok(unsafe { WStr::from_units_unchecked(units) })
+    }
+
+    /// Returns the underlying UTF-16 code units.
+    pub fn as_units(&self) -> &[u16] {
+        &self.units
+    }
+}
+
+impl WString {
+    /// Creates a new, empty `WString`.
+    pub fn new() -> WString {
+        WString { units: Vec::new() }
+    }
+}
+
+impl Deref for WString {
+    type Target = WStr;
+    fn deref(&self) -> &WStr {
+        unsafe { WStr::from_units_unchecked(&self.units) }
+    }
+}
+
+impl Borrow<WStr> for WString {
+    fn borrow(&self) -> &WStr {
+        self
+    }
+}
+
+impl ToOwned for WStr {
+    type Owned = WString;
+    fn to_owned(&self) -> WString {
+        WString { units: self.units.to_vec() }
+    }
+}
+
+impl Len for WStr {
+    fn len(&self) -> usize {
+        self.units.len()
+    }
+}
+
+impl DefaultRef for WStr {
+    fn default_ref() -> &'static WStr {
+        static EMPTY: [u16; 0] = [];
+        unsafe { WStr::from_units_unchecked(&EMPTY) }
+    }
+}
+
+impl StrLike for WStr {
+    type Data = [u16];
+    type OwnedData = Vec<u16>;
+
+    type ConvError = Utf16Error;
+
+    fn to_data(&self) -> &[u16] {
+        &self.units
+    }
+    fn from_data(data: &[u16]) -> Result<&WStr, Utf16Error> {
+        WStr::from_units(data)
+    }
+    unsafe fn from_data_unchecked(data: &[u16]) -> &WStr {
+        WStr::from_units_unchecked(data)
+    }
+
+    fn is_boundary(&self, index: usize) -> bool {
+        match self.units.get(index) {
+            // A low surrogate only ever appears as the second half of a pair,
+            // so splitting right before one would cut the pair in two.
+            Some(&unit) => !(0xDC00..=0xDFFF).contains(&unit),
+            None => true,
+        }
+    }
+    fn boundary_before(&self, index: usize) -> usize {
+        let mut i = index.min(self.units.len());
+        if !self.is_boundary(i) {
+            i -= 1;
+        }
+        i
+    }
+}
+
+// Each `WStr` is independently validated and surrogate pairs never span a
+// boundary between two strings, so concatenating the packed `u16` data is
+// equivalent to concatenating the strings themselves.
+unsafe impl DataConcat for WStr {}
+
+
+/// Error returned when validating bytes as WTF-8, the encoding `OsStr` uses
+/// internally on Windows: ordinary UTF-8, plus three-byte sequences for
+/// otherwise-unpaired UTF-16 surrogates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wtf8Error {
+    valid_up_to: usize,
+}
+
+impl Wtf8Error {
+    /// The index up to which `data` is guaranteed to be valid WTF-8.
+    pub fn valid_up_to(&self) -> usize {
+        self.valid_up_to
+    }
+}
+
+impl fmt::Display for Wtf8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid WTF-8 sequence starting at index {}", self.valid_up_to)
+    }
+}
+
+#[cfg(unix)]
+mod os_str_impl {
+    use std::ffi::OsStr;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+
+    use super::{StrLike, StrLikeMut};
+
+    // On Unix, `OsStr` is an arbitrary, unvalidated byte string, so storing
+    // and retrieving it is a no-op in both directions.
+    impl StrLike for OsStr {
+        type Data = [u8];
+        type OwnedData = Vec<u8>;
+
+        type ConvError = !;
+
+        fn to_data(&self) -> &[u8] {
+            self.as_bytes()
+        }
+        fn from_data(data: &[u8]) -> Result<&OsStr, !> {
+            Ok(OsStr::from_bytes(data))
+        }
+        unsafe fn from_data_unchecked(data: &[u8]) -> &OsStr {
+            OsStr::from_bytes(data)
+        }
+    }
+
+    impl StrLikeMut for OsStr {
+        // `OsStrExt` has no mutable counterpart to `from_bytes`, so there is
+        // no safe way to get here other than a transmute; sound because
+        // `OsStr` is a thin, unvalidated wrapper around its bytes on Unix.
+        unsafe fn to_data_mut(&mut self) -> &mut [u8] {
+            mem::transmute(self)
+        }
+        fn from_data_mut(data: &mut [u8]) -> Result<&mut OsStr, !> {
+            Ok(unsafe { mem::transmute(data) })
+        }
+        unsafe fn from_data_mut_unchecked(data: &mut [u8]) -> &mut OsStr {
+            mem::transmute(data)
+        }
+    }
+}
+
+#[cfg(windows)]
+mod os_str_impl {
+    use std::ffi::OsStr;
+    use std::mem;
+
+    use super::{StrLike, StrLikeMut, Wtf8Error, utf8_like_boundary_before};
+
+    /// Reinterprets an `OsStr` as its WTF-8 bytes without copying.
+    ///
+    /// # Safety / FIXME
+    ///
+    /// Unlike the `str` <-> `[u8]` transmute elsewhere in this file, std does
+    /// *not* document or guarantee that `OsStr`'s Windows representation is
+    /// layout-compatible with `[u8]`; this relies on the current standard
+    /// library implementation (`OsStr` -> platform `Slice` -> `Wtf8`, each
+    /// `#[repr(transparent)]` over the next) holding across std versions.
+    /// This is the same trick crates like `os_str_bytes` used before std
+    /// offered no safe equivalent, and there still isn't one.
+    ///
+    /// This path is `#[cfg(windows)]`-only and has not been exercised on a
+    /// Windows target or std version in this change; it must be verified
+    /// with a Windows build/test run before merge.
+    unsafe fn os_str_as_wtf8(s: &OsStr) -> &[u8] {
+        mem::transmute(s)
+    }
+
+    /// Decodes a 3-byte UTF-8/WTF-8 sequence into its code point, using each
+    /// byte's real bits (no assumptions about which one it is).
+    fn decode_wtf8_3(b0: u8, b1: u8, b2: u8) -> u32 {
+        ((b0 as u32 & 0x0F) << 12) | ((b1 as u32 & 0x3F) << 6) | (b2 as u32 & 0x3F)
+    }
+
+    /// Validates `data` as WTF-8: ordinary UTF-8, plus three-byte encodings of
+    /// lone surrogates (`0xD800..=0xDFFF`) that must not actually complete a
+    /// valid pair, since a real pair is required to be encoded as a 4-byte
+    /// sequence instead.
+    fn validate_wtf8(data: &[u8]) -> Result<(), Wtf8Error> {
+        let mut i = 0;
+        while i < data.len() {
+            let b0 = data[i];
+            if b0 < 0x80 {
+                i += 1;
+                continue;
+            }
+            let width = match b0 {
+                0xC2..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF4 => 4,
+                _ => return Err(Wtf8Error { valid_up_to: i }),
+            };
+            if i + width > data.len() {
+                return Err(Wtf8Error { valid_up_to: i });
+            }
+            for &b in &data[i + 1..i + width] {
+                if b & 0b1100_0000 != 0b1000_0000 {
+                    return Err(Wtf8Error { valid_up_to: i });
+                }
+            }
+            if width == 3 {
+                // Reject overlong encodings: the shortest encoding of
+                // U+0000..=U+07FF uses at most 2 bytes, so a leading `0xE0`
+                // must be followed by a continuation byte of at least `0xA0`.
+                if b0 == 0xE0 && data[i + 1] < 0xA0 {
+                    return Err(Wtf8Error { valid_up_to: i });
+                }
+                let surrogate = decode_wtf8_3(b0, data[i + 1], data[i + 2]);
+                if (0xD800..=0xDBFF).contains(&surrogate) {
+                    // A lone high surrogate must not be immediately followed
+                    // by its low surrogate: that pair must be encoded as one
+                    // 4-byte sequence instead, never as two 3-byte ones.
+                    if let [0xED, b1, b2, ..] = data[i + width..] {
+                        let low = decode_wtf8_3(0xED, b1, b2);
+                        if (0xDC00..=0xDFFF).contains(&low) {
+                            return Err(Wtf8Error { valid_up_to: i });
+                        }
+                    }
+                }
+            } else if width == 4 {
+                // Reject overlong encodings (shortest encoding of
+                // U+0000..=U+FFFF uses at most 3 bytes: a leading `0xF0` must
+                // be followed by a continuation byte of at least `0x90`) and
+                // encodings above U+10FFFF (a leading `0xF4` must be followed
+                // by a continuation byte of at most `0x8F`).
+                if (b0 == 0xF0 && data[i + 1] < 0x90) || (b0 == 0xF4 && data[i + 1] > 0x8F) {
+                    return Err(Wtf8Error { valid_up_to: i });
+                }
+            }
+            i += width;
+        }
+        Ok(())
+    }
+
+    impl StrLike for OsStr {
+        type Data = [u8];
+        type OwnedData = Vec<u8>;
+
+        type ConvError = Wtf8Error;
+
+        fn to_data(&self) -> &[u8] {
+            unsafe { os_str_as_wtf8(self) }
+        }
+        fn from_data(data: &[u8]) -> Result<&OsStr, Wtf8Error> {
+            validate_wtf8(data)?;
+            Ok(unsafe { Self::from_data_unchecked(data) })
+        }
+        unsafe fn from_data_unchecked(data: &[u8]) -> &OsStr {
+            mem::transmute(data)
+        }
+
+        fn is_boundary(&self, index: usize) -> bool {
+            let bytes = StrLike::to_data(self);
+            match bytes.get(index) {
+                Some(&b) => b & 0b1100_0000 != 0b1000_0000,
+                None => true,
+            }
+        }
+        fn boundary_before(&self, index: usize) -> usize {
+            utf8_like_boundary_before(StrLike::to_data(self), index)
+        }
+    }
+
+    impl StrLikeMut for OsStr {
+        unsafe fn to_data_mut(&mut self) -> &mut [u8] {
+            mem::transmute(self)
+        }
+        fn from_data_mut(data: &mut [u8]) -> Result<&mut OsStr, Wtf8Error> {
+            validate_wtf8(data)?;
+            Ok(unsafe { mem::transmute(data) })
+        }
+        unsafe fn from_data_mut_unchecked(data: &mut [u8]) -> &mut OsStr {
+            mem::transmute(data)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::validate_wtf8;
+
+        #[test]
+        fn accepts_lone_high_surrogate() {
+            // U+D800 encoded as a bare 3-byte sequence, at end of input: WTF-8
+            // explicitly allows unpaired surrogates, unlike UTF-8.
+            assert!(validate_wtf8(&[0xED, 0xA0, 0x80]).is_ok());
+        }
+
+        #[test]
+        fn accepts_lone_low_surrogate() {
+            // U+DC00 with no preceding high surrogate.
+            assert!(validate_wtf8(&[0xED, 0xB0, 0x80]).is_ok());
+        }
+
+        #[test]
+        fn accepts_high_surrogate_followed_by_non_surrogate() {
+            // A high surrogate followed by an ASCII byte, not its low surrogate.
+            assert!(validate_wtf8(&[0xED, 0xA0, 0x80, b'x']).is_ok());
+        }
+
+        #[test]
+        fn rejects_overlong_2_byte() {
+            // Overlong encoding of U+0000 (should be a single 0x00 byte).
+            assert!(validate_wtf8(&[0xC0, 0x80]).is_err());
+        }
+
+        #[test]
+        fn rejects_overlong_3_byte() {
+            // Overlong encoding of U+0000.
+            assert!(validate_wtf8(&[0xE0, 0x80, 0x80]).is_err());
+        }
+
+        #[test]
+        fn rejects_overlong_4_byte() {
+            // Overlong encoding of U+0000.
+            assert!(validate_wtf8(&[0xF0, 0x80, 0x80, 0x80]).is_err());
+        }
+
+        #[test]
+        fn rejects_out_of_range_4_byte() {
+            // Decodes past U+10FFFF, the maximum valid code point.
+            assert!(validate_wtf8(&[0xF4, 0x90, 0x80, 0x80]).is_err());
+        }
+
+        #[test]
+        fn accepts_valid_4_byte() {
+            // U+10000, the smallest valid 4-byte code point.
+            assert!(validate_wtf8(&[0xF0, 0x90, 0x80, 0x80]).is_ok());
+        }
+
+        #[test]
+        fn rejects_surrogate_pair_as_two_3_byte_sequences() {
+            // U+10000 split into a high and low surrogate, each encoded as its
+            // own 3-byte sequence: this must be rejected, since a real pair is
+            // required to be encoded as one 4-byte sequence instead.
+            assert!(validate_wtf8(&[0xED, 0xA0, 0x80, 0xED, 0xB0, 0x80]).is_err());
+        }
+
+        #[test]
+        fn accepts_lone_surrogates_that_do_not_form_a_pair() {
+            // A high surrogate followed by a 3-byte sequence that is not its
+            // low surrogate: both are individually valid WTF-8.
+            assert!(validate_wtf8(&[0xED, 0xA0, 0x80, 0xED, 0x9F, 0xBF]).is_ok());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod utf16_tests {
+    use super::validate_utf16;
+
+    #[test]
+    fn rejects_lone_high_surrogate() {
+        assert!(validate_utf16(&[0xD800]).is_err());
+    }
+
+    #[test]
+    fn rejects_lone_low_surrogate() {
+        assert!(validate_utf16(&[0xDC00]).is_err());
+    }
+
+    #[test]
+    fn rejects_high_surrogate_followed_by_non_surrogate() {
+        // A high surrogate followed by an ordinary BMP unit instead of a low surrogate.
+        assert!(validate_utf16(&[0xD800, 0x0041]).is_err());
+    }
+
+    #[test]
+    fn accepts_valid_pair() {
+        assert!(validate_utf16(&[0xD800, 0xDC00]).is_ok());
+    }
+
+    #[test]
+    fn accepts_bmp_units() {
+        assert!(validate_utf16(&[0x0041, 0x0042, 0x0043]).is_ok());
+    }
+}